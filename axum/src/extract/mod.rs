@@ -0,0 +1,5 @@
+//! Types and traits for extracting data from requests.
+
+mod state;
+
+pub use self::state::{FromRefAsync, State, StateAsync, TryFromRef};
@@ -6,6 +6,72 @@ use std::{
     ops::{Deref, DerefMut},
 };
 
+/// Support for fallibly deriving a substate from some larger state.
+///
+/// This is the fallible counterpart to [`FromRef`]. Implement it when deriving your substate
+/// can fail, e.g. when it involves looking a value up in a map or validating that some feature
+/// is enabled.
+///
+/// Every type that implements [`FromRef`] also implements `TryFromRef`, with
+/// [`Rejection`](TryFromRef::Rejection) set to [`Infallible`], so existing [`FromRef`]
+/// implementations keep working unchanged.
+pub trait TryFromRef<OuterState>: Sized {
+    /// What rejection to use if extraction of the substate fails.
+    type Rejection;
+
+    /// Try to perform the conversion.
+    fn try_from_ref(outer_state: &OuterState) -> Result<Self, Self::Rejection>;
+}
+
+impl<OuterState, InnerState> TryFromRef<OuterState> for InnerState
+where
+    InnerState: FromRef<OuterState>,
+{
+    type Rejection = Infallible;
+
+    fn try_from_ref(outer_state: &OuterState) -> Result<Self, Self::Rejection> {
+        Ok(InnerState::from_ref(outer_state))
+    }
+}
+
+/// Support for asynchronously deriving a substate from some larger state.
+///
+/// This is the async counterpart to [`TryFromRef`]. Implement it when deriving your substate
+/// needs to do actual async work, such as checking out a connection from a pool or fetching a
+/// short-lived credential, rather than just cloning something out of the outer state.
+///
+/// Every type that implements [`TryFromRef`] (and therefore every [`FromRef`] implementor) also
+/// implements `FromRefAsync` for any outer state that is `Sync`, via a blanket impl that runs the
+/// synchronous conversion without awaiting anything. This is what [`StateAsync`] uses to accept ordinary synchronous substates
+/// alongside ones that need to do real async work.
+#[async_trait]
+pub trait FromRefAsync<OuterState>: Sized {
+    /// What rejection to use if extraction of the substate fails.
+    type Rejection;
+
+    /// Try to perform the conversion, asynchronously.
+    async fn from_ref_async(
+        outer_state: &OuterState,
+        parts: &mut Parts,
+    ) -> Result<Self, Self::Rejection>;
+}
+
+#[async_trait]
+impl<OuterState, InnerState> FromRefAsync<OuterState> for InnerState
+where
+    InnerState: TryFromRef<OuterState>,
+    OuterState: Sync,
+{
+    type Rejection = <InnerState as TryFromRef<OuterState>>::Rejection;
+
+    async fn from_ref_async(
+        outer_state: &OuterState,
+        _parts: &mut Parts,
+    ) -> Result<Self, Self::Rejection> {
+        InnerState::try_from_ref(outer_state)
+    }
+}
+
 /// Extractor for state.
 ///
 /// See ["Accessing state in middleware"][state-from-middleware] for how to  
@@ -228,22 +294,58 @@ use std::{
 ///
 /// In general however we recommend you implement `Clone` for all your state types to avoid
 /// potential type errors.
+///
+/// # Fallible substates
+///
+/// Deriving a substate via [`FromRef`] can never fail. If yours can fail -- for example you're
+/// looking the substate up in a map keyed by a header, or checking that some feature is enabled
+/// -- implement [`TryFromRef`] instead:
+///
+/// ```
+/// use axum::extract::{State, TryFromRef};
+/// use http::StatusCode;
+///
+/// #[derive(Clone)]
+/// struct AppState {
+///     api_state: Option<ApiState>,
+/// }
+///
+/// #[derive(Clone)]
+/// struct ApiState {}
+///
+/// impl TryFromRef<AppState> for ApiState {
+///     type Rejection = (StatusCode, &'static str);
+///
+///     fn try_from_ref(app_state: &AppState) -> Result<Self, Self::Rejection> {
+///         app_state
+///             .api_state
+///             .clone()
+///             .ok_or((StatusCode::NOT_FOUND, "api is disabled"))
+///     }
+/// }
+///
+/// async fn api_users(State(api_state): State<ApiState>) {
+///     // if `api_state` is `None` on `AppState`, this handler is never called and the
+///     // rejection above is returned instead
+/// }
+/// ```
+///
 #[derive(Debug, Default, Clone, Copy)]
 pub struct State<S>(pub S);
 
 #[async_trait]
 impl<OuterState, InnerState> FromRequestParts<OuterState> for State<InnerState>
 where
-    InnerState: FromRef<OuterState>,
+    InnerState: TryFromRef<OuterState>,
     OuterState: Send + Sync,
 {
-    type Rejection = Infallible;
+    type Rejection = <InnerState as TryFromRef<OuterState>>::Rejection;
 
     async fn from_request_parts(
         _parts: &mut Parts,
         state: &OuterState,
     ) -> Result<Self, Self::Rejection> {
-        let inner_state = InnerState::from_ref(state);
+        let inner_state = InnerState::try_from_ref(state)?;
         Ok(Self(inner_state))
     }
 }
@@ -261,3 +363,179 @@ impl<S> DerefMut for State<S> {
         &mut self.0
     }
 }
+
+/// Extractor for state whose substate derivation needs to do async work.
+///
+/// [`State`] derives substates through the synchronous [`TryFromRef`] (and, by extension,
+/// [`FromRef`]), so it can't be used when producing a substate requires awaiting something, such
+/// as checking out a connection from a pool or fetching a short-lived credential. `StateAsync`
+/// is the same extractor, but built on [`FromRefAsync`] instead, so its `from_request_parts` can
+/// actually await the derivation rather than requiring handlers to clone a pool handle and await
+/// later in the handler body.
+///
+/// Keeping this as a separate extractor (rather than changing [`State`] itself) means the common
+/// case of cloning a substate out of `Clone` state stays on the plain synchronous path, with no
+/// extra boxed future for state that never needs one.
+///
+/// ```
+/// use axum::extract::{StateAsync, FromRefAsync};
+/// use http::{StatusCode, request::Parts};
+/// use async_trait::async_trait;
+///
+/// #[derive(Clone)]
+/// struct AppState {
+///     pool: ConnectionPool,
+/// }
+///
+/// #[derive(Clone)]
+/// struct ConnectionPool {}
+///
+/// impl ConnectionPool {
+///     async fn acquire(&self) -> Result<PooledConnection, (StatusCode, &'static str)> {
+///         // ...
+///         # unimplemented!()
+///     }
+/// }
+///
+/// struct PooledConnection {}
+///
+/// #[async_trait]
+/// impl FromRefAsync<AppState> for PooledConnection {
+///     type Rejection = (StatusCode, &'static str);
+///
+///     async fn from_ref_async(
+///         app_state: &AppState,
+///         _parts: &mut Parts,
+///     ) -> Result<Self, Self::Rejection> {
+///         app_state.pool.acquire().await
+///     }
+/// }
+///
+/// async fn handler(StateAsync(conn): StateAsync<PooledConnection>) {
+///     // `conn` was checked out of the pool while extracting the request
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StateAsync<S>(pub S);
+
+#[async_trait]
+impl<OuterState, InnerState> FromRequestParts<OuterState> for StateAsync<InnerState>
+where
+    InnerState: FromRefAsync<OuterState>,
+    OuterState: Send + Sync,
+{
+    type Rejection = <InnerState as FromRefAsync<OuterState>>::Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &OuterState,
+    ) -> Result<Self, Self::Rejection> {
+        let inner_state = InnerState::from_ref_async(state, parts).await?;
+        Ok(Self(inner_state))
+    }
+}
+
+impl<S> Deref for StateAsync<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> DerefMut for StateAsync<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_infallible<S, T>()
+    where
+        T: FromRequestParts<S, Rejection = Infallible>,
+    {
+    }
+
+    #[derive(Clone)]
+    struct AppState {
+        value: i32,
+    }
+
+    #[test]
+    fn state_rejection_is_infallible_for_clone_state() {
+        assert_infallible::<AppState, State<AppState>>();
+    }
+
+    #[tokio::test]
+    async fn state_extracts_via_clone() {
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        let app_state = AppState { value: 1 };
+
+        let State(AppState { value }) = State::<AppState>::from_request_parts(&mut parts, &app_state)
+            .await
+            .unwrap();
+
+        assert_eq!(value, 1);
+    }
+
+    struct Inner;
+
+    impl TryFromRef<AppState> for Inner {
+        type Rejection = &'static str;
+
+        fn try_from_ref(outer_state: &AppState) -> Result<Self, Self::Rejection> {
+            if outer_state.value > 0 {
+                Ok(Inner)
+            } else {
+                Err("value must be positive")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn try_from_ref_rejection_surfaces() {
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        let app_state = AppState { value: 0 };
+
+        let rejection = State::<Inner>::from_request_parts(&mut parts, &app_state)
+            .await
+            .unwrap_err();
+
+        assert_eq!(rejection, "value must be positive");
+    }
+
+    #[derive(Clone)]
+    struct Pool {
+        value: i32,
+    }
+
+    struct Conn(i32);
+
+    #[async_trait]
+    impl FromRefAsync<Pool> for Conn {
+        type Rejection = Infallible;
+
+        async fn from_ref_async(
+            outer_state: &Pool,
+            _parts: &mut Parts,
+        ) -> Result<Self, Self::Rejection> {
+            // stand in for actually awaiting something, e.g. checking out a connection
+            Ok(Conn(outer_state.value))
+        }
+    }
+
+    #[tokio::test]
+    async fn from_ref_async_runs_during_extraction() {
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        let pool = Pool { value: 42 };
+
+        let StateAsync(Conn(value)) = StateAsync::<Conn>::from_request_parts(&mut parts, &pool)
+            .await
+            .unwrap();
+
+        assert_eq!(value, 42);
+    }
+}